@@ -1,13 +1,72 @@
-use std::error::Error;
+use std::{env, error::Error, fs};
 
 use tokio::sync::mpsc;
-use tonic::Request;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity};
 
-use roolz::api::v1alpha::service::{RulesServiceClient, SessionRequest};
+use roolz::api::v1alpha::service::SessionRequest;
+use roolz::session::Session;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let mut client = RulesServiceClient::connect("http://127.0.0.1:1234").await?;
+    // vsock connections are dialed directly; the resilient reconnecting session
+    // wraps the TCP/TLS endpoint.
+    #[cfg(feature = "vsock")]
+    if let Ok(addr) = env::var("ROOLZ_VSOCK") {
+        return drive_vsock(&addr).await;
+    }
+
+    let mut session = Session::new(build_endpoint()?);
+
+    let (tx, out_rx) = mpsc::unbounded_channel();
+    let (in_tx, mut inbound) = mpsc::unbounded_channel();
+    tokio::spawn(async move { session.run(out_rx, in_tx).await });
+
+    tx.send(SessionRequest::default())?;
+    while let Some(resp) = inbound.recv().await {
+        println!("{:?}", resp);
+        tx.send(SessionRequest::default())?;
+    }
+
+    Ok(())
+}
+
+/// Build the TCP endpoint, enabling rustls-backed transport when a CA is
+/// configured and presenting a client certificate for mutual TLS.
+fn build_endpoint() -> Result<Endpoint, Box<dyn Error>> {
+    let uri = env::var("ROOLZ_SERVER").unwrap_or_else(|_| "http://127.0.0.1:1234".into());
+    let mut endpoint = Endpoint::from_shared(uri)?;
+
+    if let Ok(ca) = env::var("ROOLZ_TLS_CA") {
+        let mut tls = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(fs::read(ca)?));
+        if let (Ok(cert), Ok(key)) = (env::var("ROOLZ_TLS_CERT"), env::var("ROOLZ_TLS_KEY")) {
+            tls = tls.identity(Identity::from_pem(fs::read(cert)?, fs::read(key)?));
+        }
+        if let Ok(domain) = env::var("ROOLZ_TLS_DOMAIN") {
+            tls = tls.domain_name(domain);
+        }
+        endpoint = endpoint.tls_config(tls);
+    }
+
+    Ok(endpoint)
+}
+
+/// Dial a `<cid>:<port>` AF_VSOCK address and run a single (non-resilient) session.
+#[cfg(feature = "vsock")]
+async fn drive_vsock(addr: &str) -> Result<(), Box<dyn Error>> {
+    use roolz::api::v1alpha::service::RulesServiceClient;
+    use tonic::Request;
+
+    let mut parts = addr.splitn(2, ':');
+    let cid: u32 = parts.next().ok_or("expected <cid>:<port>")?.parse()?;
+    let port: u32 = parts.next().ok_or("expected <cid>:<port>")?.parse()?;
+
+    // The URI is ignored by the connector but still required by the builder.
+    let channel = Endpoint::from_static("http://[::1]:1234")
+        .connect_with_connector(tower::service_fn(move |_| {
+            tokio_vsock::VsockStream::connect(cid, port)
+        }))
+        .await?;
+    let mut client = RulesServiceClient::new(channel);
 
     let (tx, mut rx) = mpsc::unbounded_channel();
     let handler = async_stream::stream! {
@@ -19,10 +78,10 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let response = client.session(Request::new(handler)).await?;
     let mut inbound = response.into_inner();
 
-    tx.send(SessionRequest::default()).unwrap();
+    tx.send(SessionRequest::default())?;
     while let Some(resp) = inbound.message().await? {
         println!("{:?}", resp);
-        tx.send(SessionRequest::default()).unwrap();
+        tx.send(SessionRequest::default())?;
     }
 
     Ok(())
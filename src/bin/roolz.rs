@@ -1,9 +1,11 @@
 use std::{
     cell::Cell,
     error::Error,
+    fmt, fs,
     net::SocketAddr,
     path::PathBuf,
     pin::Pin,
+    sync::Arc,
     thread::{self, JoinHandle},
     time::Duration,
 };
@@ -14,40 +16,54 @@ use futures::future::FutureExt;
 use futures_core::{Future, Stream};
 use notify::{self, DebouncedEvent, RecursiveMode, Watcher};
 use tokio::{stream::StreamExt, sync::mpsc};
-use tonic::{transport::Server, Request, Response, Status, Streaming};
+use tonic::{
+    transport::{Certificate, Identity, Server, ServerTlsConfig},
+    Request, Response, Status, Streaming,
+};
 use uuid::Uuid;
 
 use roolz::api::v1alpha::service::{
     RulesService, RulesServiceServer, SessionRequest, SessionResponse,
 };
+use roolz::engine::Engine;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let opts = Opts::parse();
     simple_logger::init_with_level(opts.log_level).expect("Failed to initialize logger");
 
-    let sig_handler = CtrlC::new().expect("Cannot create signal handler").shared();
-
-    tokio::try_join!(
-        start_server(opts.address, sig_handler.clone()),
-        watch_files(opts.rules, sig_handler.clone()),
-        watch_files(opts.facts, sig_handler),
-    )?;
-
-    Ok(())
+    match opts.command {
+        Command::Serve(serve) => serve.run().await,
+        Command::Lsp => roolz::lsp::run().await,
+    }
 }
 
 /// A distributed rules engine
 #[derive(Clap)]
 #[clap(version = "0.1.0")]
 struct Opts {
-    /// todo
-    address: SocketAddr,
-
     /// todo
     #[clap(short = "l", long = "log-level", default_value = "info")]
     log_level: log::Level,
 
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Clap)]
+enum Command {
+    /// Serve the rules engine over the network
+    Serve(ServeOpts),
+
+    /// Run as a language server over stdio, publishing diagnostics for .roolz files
+    Lsp,
+}
+
+#[derive(Clap)]
+struct ServeOpts {
+    /// todo
+    address: SocketAddr,
+
     /// todo
     #[clap(short = "r", long = "rules", required = true)]
     rules: Vec<PathBuf>,
@@ -55,10 +71,116 @@ struct Opts {
     /// todo
     #[clap(short = "f", long = "facts", required = true)]
     facts: Vec<PathBuf>,
+
+    /// PEM-encoded server certificate chain used to enable TLS transport
+    #[clap(long = "tls-cert", requires = "tls-key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM-encoded private key matching `--tls-cert`
+    #[clap(long = "tls-key", requires = "tls-cert")]
+    tls_key: Option<PathBuf>,
+
+    /// PEM-encoded CA used to require and verify client certificates (mutual TLS)
+    #[clap(long = "tls-client-ca", requires = "tls-cert")]
+    tls_client_ca: Option<PathBuf>,
+
+    /// AF_VSOCK listener as <cid>:<port>, for serving guests from a hypervisor
+    #[cfg(feature = "vsock")]
+    #[clap(long = "vsock")]
+    vsock: Option<VsockEndpoint>,
+}
+
+/// A parsed `<cid>:<port>` AF_VSOCK address.
+#[cfg(feature = "vsock")]
+#[derive(Debug, Clone, Copy)]
+struct VsockEndpoint {
+    cid: u32,
+    port: u32,
+}
+
+#[cfg(feature = "vsock")]
+impl std::str::FromStr for VsockEndpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        let mut parts = s.splitn(2, ':');
+        let cid = parts
+            .next()
+            .ok_or_else(|| "expected <cid>:<port>".to_string())?
+            .parse()
+            .map_err(|_| "invalid vsock cid".to_string())?;
+        let port = parts
+            .next()
+            .ok_or_else(|| "expected <cid>:<port>".to_string())?
+            .parse()
+            .map_err(|_| "invalid vsock port".to_string())?;
+        Ok(VsockEndpoint { cid, port })
+    }
+}
+
+impl ServeOpts {
+    async fn run(self) -> Result<(), Box<dyn Error>> {
+        let sig_handler = CtrlC::new().expect("Cannot create signal handler").shared();
+
+        let tls = Tls::from_opts(&self)?;
+        let engine = Arc::new(Engine::new());
+
+        #[cfg(feature = "vsock")]
+        tokio::try_join!(
+            start_server(self.address, tls, engine.clone(), sig_handler.clone()),
+            start_vsock_server(self.vsock, engine.clone(), sig_handler.clone()),
+            watch_files(self.rules, engine.clone(), sig_handler.clone()),
+            watch_files(self.facts, engine, sig_handler),
+        )?;
+
+        #[cfg(not(feature = "vsock"))]
+        tokio::try_join!(
+            start_server(self.address, tls, engine.clone(), sig_handler.clone()),
+            watch_files(self.rules, engine.clone(), sig_handler.clone()),
+            watch_files(self.facts, engine, sig_handler),
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Resolved transport security for the serving task.
+struct Tls {
+    identity: Identity,
+    client_ca: Option<Certificate>,
+}
+
+impl Tls {
+    /// Load the TLS material named on the command line, if any was supplied.
+    fn from_opts(opts: &ServeOpts) -> Result<Option<Tls>, Box<dyn Error>> {
+        let (cert, key) = match (&opts.tls_cert, &opts.tls_key) {
+            (Some(cert), Some(key)) => (cert, key),
+            _ => return Ok(None),
+        };
+        let identity = Identity::from_pem(fs::read(cert)?, fs::read(key)?);
+        let client_ca = match &opts.tls_client_ca {
+            Some(ca) => Some(Certificate::from_pem(fs::read(ca)?)),
+            None => None,
+        };
+        Ok(Some(Tls {
+            identity,
+            client_ca,
+        }))
+    }
+
+    /// Build the tonic `ServerTlsConfig`, enabling mutual auth when a client CA is present.
+    fn config(self) -> ServerTlsConfig {
+        let config = ServerTlsConfig::new().identity(self.identity);
+        match self.client_ca {
+            Some(ca) => config.client_ca_root(ca),
+            None => config,
+        }
+    }
 }
 
 async fn watch_files<S: Future<Output = ()>>(
     paths: Vec<PathBuf>,
+    engine: Arc<Engine>,
     sig_handler: S,
 ) -> Result<(), Box<dyn Error>> {
     let watcher_id = Uuid::new_v4();
@@ -94,12 +216,16 @@ async fn watch_files<S: Future<Output = ()>>(
                 match result? {
                     DebouncedEvent::Write(path) => {
                         log::debug!("File updated: {:?}", path);
+                        engine.reload(&path);
                     }
                     DebouncedEvent::Remove(path) => {
                         log::debug!("File removed: {:?}", path);
+                        engine.forget(&path);
                     }
                     DebouncedEvent::Rename(old, new) => {
                         log::debug!("File moved: {:?} to {:?}", old, new);
+                        engine.forget(&old);
+                        engine.reload(&new);
                     }
                     _ => {}
                 }
@@ -118,12 +244,20 @@ async fn watch_files<S: Future<Output = ()>>(
 
 async fn start_server<S: Future<Output = ()>>(
     addr: SocketAddr,
+    tls: Option<Tls>,
+    engine: Arc<Engine>,
     sig_handler: S,
 ) -> Result<(), Box<dyn Error>> {
-    let service = RulesServiceServer::new(RulesServiceState {});
+    let service = RulesServiceServer::new(RulesServiceState { engine });
+
+    let mut builder = Server::builder();
+    if let Some(tls) = tls {
+        log::info!("Enabling TLS transport...");
+        builder = builder.tls_config(tls.config());
+    }
 
     log::info!("Starting server on {}...", addr);
-    Server::builder()
+    builder
         .add_service(service)
         .serve_with_shutdown(addr, sig_handler)
         .await?;
@@ -132,8 +266,61 @@ async fn start_server<S: Future<Output = ()>>(
     Ok(())
 }
 
-#[derive(Debug)]
-struct RulesServiceState;
+/// Serve the same `RulesService` over an AF_VSOCK listener, so the engine is
+/// reachable from VMs/enclaves without TCP/IP. A `None` endpoint simply waits
+/// for shutdown so the serving task can be joined unconditionally.
+#[cfg(feature = "vsock")]
+async fn start_vsock_server<S: Future<Output = ()>>(
+    endpoint: Option<VsockEndpoint>,
+    engine: Arc<Engine>,
+    sig_handler: S,
+) -> Result<(), Box<dyn Error>> {
+    let endpoint = match endpoint {
+        Some(endpoint) => endpoint,
+        None => {
+            sig_handler.await;
+            return Ok(());
+        }
+    };
+
+    let service = RulesServiceServer::new(RulesServiceState { engine });
+    let listener = tokio_vsock::VsockListener::bind(endpoint.cid, endpoint.port)?;
+
+    log::info!(
+        "Starting vsock server on {}:{}...",
+        endpoint.cid,
+        endpoint.port
+    );
+    Server::builder()
+        .add_service(service)
+        .serve_with_incoming_shutdown(listener.incoming(), sig_handler)
+        .await?;
+    log::info!("Shutting down vsock server...");
+
+    Ok(())
+}
+
+struct RulesServiceState {
+    engine: Arc<Engine>,
+}
+
+/// The verified leaf certificate of a mutually-authenticated caller, threaded
+/// into the session state for downstream per-caller rule scoping.
+#[derive(Debug, Clone)]
+struct PeerIdentity(Certificate);
+
+impl fmt::Display for PeerIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The raw DER is not human-readable, so render the whole leaf
+        // certificate as hex, byte for byte. This is injective — two distinct certs
+        // never collide — so it is safe to key per-caller scoping on.
+        write!(f, "peer:")?;
+        for byte in self.0.as_ref() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
 
 #[tonic::async_trait]
 impl RulesService for RulesServiceState {
@@ -142,15 +329,50 @@ impl RulesService for RulesServiceState {
 
     async fn session(
         &self,
-        request: Request<Streaming<SessionRequest>>,
+        mut request: Request<Streaming<SessionRequest>>,
     ) -> Result<Response<Self::SessionStream>, Status> {
+        // When mutual TLS is configured the transport has already verified the
+        // client certificate chain; capture the leaf so rule evaluation can
+        // later be scoped to the authenticated caller. This must happen before
+        // `into_inner` consumes the request (and with it the peer certs).
+        let identity = request.peer_certs().and_then(|certs| {
+            certs.first().map(|leaf| {
+                let identity = PeerIdentity(leaf.clone());
+                log::debug!("Authenticated peer: {:?}", identity);
+                identity.to_string()
+            })
+        });
+
         let mut stream = request.into_inner();
+        let engine = self.engine.clone();
 
-        let handler = async_stream::try_stream! {
-            while let Some(req) = stream.next().await {
+        // The first request may carry a resume token identifying an earlier
+        // session; reuse its id so evaluation continues rather than restarts.
+        let id = match stream.next().await {
+            Some(Ok(req)) => {
                 log::debug!("{:?}", req);
-                yield SessionResponse::default();
+                Uuid::parse_str(&req.resume_token).unwrap_or_else(|_| Uuid::new_v4())
+            }
+            Some(Err(status)) => return Err(status),
+            None => Uuid::new_v4(),
+        };
+        let (epoch, mut updates) = engine.register_with(id, identity);
+
+        let handler = async_stream::try_stream! {
+            loop {
+                tokio::select! {
+                    incoming = stream.next() => match incoming {
+                        Some(req) => log::debug!("{:?}", req),
+                        // Client closed the stream.
+                        None => break,
+                    },
+                    update = updates.recv() => match update {
+                        Some(response) => yield response,
+                        None => break,
+                    },
+                }
             }
+            engine.unregister(id, epoch);
         };
 
         Ok(Response::new(Box::pin(handler)))
@@ -0,0 +1,191 @@
+//! Shared rules-engine state connecting the file watchers to live sessions.
+//!
+//! The [`Engine`] owns the compiled rules/facts and a registry of connected
+//! sessions. Each session holds an [`mpsc`] sender; when a watcher re-parses a
+//! changed file the new ruleset is swapped in atomically and an update is pushed
+//! to every subscriber. Dead sessions are pruned lazily when a send fails.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, RwLock,
+    },
+};
+
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::api::v1alpha::service::SessionResponse;
+use crate::compiler::parser::{Ast, Parser};
+
+/// The live, shared state of the rules engine.
+#[derive(Default)]
+pub struct Engine {
+    /// The last-good AST for each watched file, keyed by its path.
+    ruleset: RwLock<HashMap<PathBuf, Ast>>,
+    /// Senders for every connected session.
+    sessions: Mutex<HashMap<Uuid, Subscriber>>,
+    /// Monotonic source of per-registration epochs, used to distinguish a
+    /// reconnecting handler from the one it replaced.
+    epochs: AtomicU64,
+}
+
+/// A single connected session in the registry.
+struct Subscriber {
+    /// The channel used to push ruleset updates to the session handler.
+    tx: mpsc::UnboundedSender<SessionResponse>,
+    /// The epoch of the handler that owns this entry. A reconnecting client
+    /// reuses its session id, so [`unregister`] compares epochs to avoid a
+    /// stale handler tearing down the fresh registration that replaced it.
+    epoch: u64,
+    /// The verified peer identity, present only when the caller authenticated
+    /// over mutual TLS. Retained so evaluation can later be scoped per caller.
+    identity: Option<String>,
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        Engine::default()
+    }
+
+    /// Register a new session, returning its id and the receiver it should drain
+    /// to observe ruleset updates.
+    pub fn register(&self) -> (Uuid, mpsc::UnboundedReceiver<SessionResponse>) {
+        let id = Uuid::new_v4();
+        let (_, rx) = self.register_with(id, None);
+        (id, rx)
+    }
+
+    /// Register a session under a caller-supplied id, replacing any previous
+    /// registration with the same id. This lets a reconnecting client resume its
+    /// earlier session by presenting its resume token.
+    ///
+    /// `identity` carries the verified peer identity when the caller
+    /// authenticated over mutual TLS, so later evaluation can be scoped to the
+    /// authenticated caller.
+    ///
+    /// Returns the registration's epoch alongside its receiver; the handler must
+    /// pass that epoch back to [`unregister`] so it only tears down its own
+    /// registration, never one a reconnecting client installed in its place.
+    pub fn register_with(
+        &self,
+        id: Uuid,
+        identity: Option<String>,
+    ) -> (u64, mpsc::UnboundedReceiver<SessionResponse>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let epoch = self.epochs.fetch_add(1, Ordering::Relaxed);
+        self.sessions.lock().unwrap().insert(
+            id,
+            Subscriber {
+                tx,
+                epoch,
+                identity,
+            },
+        );
+        log::debug!("Registered session {} (epoch {})", id, epoch);
+        (epoch, rx)
+    }
+
+    /// Drop a session from the registry, but only if the entry still belongs to
+    /// the handler identified by `epoch`. A reconnecting client reuses its id, so
+    /// a stale handler whose channel was replaced must not remove the live one.
+    pub fn unregister(&self, id: Uuid, epoch: u64) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if let Some(sub) = sessions.get(&id) {
+            if sub.epoch != epoch {
+                log::debug!("Session {} resumed (epoch {}); keeping it", id, sub.epoch);
+                return;
+            }
+        }
+        sessions.remove(&id);
+        log::debug!("Unregistered session {} (epoch {})", id, epoch);
+    }
+
+    /// Re-parse a changed file and, on success, atomically swap it into the
+    /// ruleset and notify every subscribed session. On failure the last-good
+    /// ruleset is kept and the diagnostics are logged.
+    pub fn reload(&self, path: &Path) {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) => {
+                log::warn!("Cannot open {:?}: {}", path, err);
+                return;
+            }
+        };
+        match Parser::parse(file) {
+            Ok(ast) => {
+                let symbols = symbols(&ast);
+                self.ruleset
+                    .write()
+                    .unwrap()
+                    .insert(path.to_path_buf(), ast);
+                log::info!("Recompiled {:?}", path);
+                self.broadcast(SessionResponse {
+                    path: path.display().to_string(),
+                    symbols,
+                    dropped: false,
+                    ..SessionResponse::default()
+                });
+            }
+            Err(diagnostics) => {
+                log::warn!(
+                    "Keeping last-good ruleset; {:?} has {} error(s):",
+                    path,
+                    diagnostics.len()
+                );
+                for diagnostic in diagnostics {
+                    log::warn!("  {}", diagnostic);
+                }
+            }
+        }
+    }
+
+    /// Forget a removed file so it no longer contributes to the ruleset.
+    pub fn forget(&self, path: &Path) {
+        if self.ruleset.write().unwrap().remove(path).is_some() {
+            log::info!("Dropped rules from {:?}", path);
+            self.broadcast(SessionResponse {
+                path: path.display().to_string(),
+                symbols: Vec::new(),
+                dropped: true,
+                ..SessionResponse::default()
+            });
+        }
+    }
+
+    /// Push an update to every session, pruning any that have disconnected.
+    fn broadcast(&self, response: SessionResponse) {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|id, sub| {
+            if sub.tx.send(response.clone()).is_err() {
+                log::debug!("Pruning disconnected session {}", id);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// The verified peer identity recorded for a session, if it authenticated
+    /// over mutual TLS. Lets evaluation be scoped to the authenticated caller.
+    pub fn identity(&self, id: Uuid) -> Option<String> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(&id)
+            .and_then(|sub| sub.identity.clone())
+    }
+}
+
+/// Flatten a compiled [`Ast`] into the declared symbol names carried in an
+/// update, so subscribers can tell what the new ruleset contains without
+/// re-fetching the file.
+fn symbols(ast: &Ast) -> Vec<String> {
+    let mut symbols = vec![format!("pkg {}", ast.package.name.join("."))];
+    symbols.extend(ast.facts.iter().map(|fact| format!("fact {}", fact.name)));
+    symbols.extend(ast.rules.iter().map(|rule| format!("rule {}", rule.name)));
+    symbols
+}
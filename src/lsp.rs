@@ -0,0 +1,175 @@
+//! A Language Server Protocol front-end for `.roolz` files.
+//!
+//! This drives [`crate::compiler::parser::Parser`] against the in-memory buffer
+//! an editor holds open, republishing diagnostics on every edit and answering
+//! `documentSymbol` with the declared `pkg`, `fact`, and `rule` names. It reuses
+//! the parser's character-accurate `(line, column)` tracking, so no separate
+//! source model is needed.
+
+use std::{collections::HashMap, error::Error, io::Cursor};
+
+use tokio::sync::Mutex;
+use tower_lsp::{
+    jsonrpc::Result,
+    lsp_types::*,
+    Client, LanguageServer, LspService, Server,
+};
+
+use crate::compiler::parser::{Ast, Parser};
+
+/// Run the language server over stdio until the client disconnects.
+pub async fn run() -> std::result::Result<(), Box<dyn Error>> {
+    let stdin = tokio::io::stdin();
+    let stdout = tokio::io::stdout();
+
+    let (service, messages) = LspService::new(|client| Backend {
+        client,
+        documents: Mutex::new(HashMap::new()),
+    });
+    Server::new(stdin, stdout)
+        .interleave(messages)
+        .serve(service)
+        .await;
+
+    Ok(())
+}
+
+struct Backend {
+    client: Client,
+    /// The latest text of every open document, keyed by URI.
+    documents: Mutex<HashMap<Url, String>>,
+}
+
+impl Backend {
+    /// Re-parse a document and publish the resulting diagnostics.
+    async fn analyze(&self, uri: Url, text: String) {
+        let diagnostics = match Parser::parse(Cursor::new(&text)) {
+            Ok(_) => Vec::new(),
+            Err(diagnostics) => diagnostics.iter().map(to_lsp_diagnostic).collect(),
+        };
+        self.documents.lock().await.insert(uri.clone(), text);
+        self.client
+            .publish_diagnostics(uri, diagnostics, None)
+            .await;
+    }
+}
+
+#[tower_lsp::async_trait]
+impl LanguageServer for Backend {
+    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+        Ok(InitializeResult {
+            capabilities: ServerCapabilities {
+                text_document_sync: Some(TextDocumentSyncCapability::Kind(
+                    TextDocumentSyncKind::Full,
+                )),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                ..ServerCapabilities::default()
+            },
+            ..InitializeResult::default()
+        })
+    }
+
+    async fn initialized(&self, _: InitializedParams) {
+        self.client
+            .log_message(MessageType::Info, "roolz language server ready")
+            .await;
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        let doc = params.text_document;
+        self.analyze(doc.uri, doc.text).await;
+    }
+
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        // We requested full-document sync, so the last change holds the whole buffer.
+        if let Some(change) = params.content_changes.into_iter().last() {
+            self.analyze(params.text_document.uri, change.text).await;
+        }
+    }
+
+    async fn document_symbol(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> Result<Option<DocumentSymbolResponse>> {
+        let uri = params.text_document.uri;
+        let text = match self.documents.lock().await.get(&uri) {
+            Some(text) => text.clone(),
+            None => return Ok(None),
+        };
+        let ast = match Parser::parse(Cursor::new(&text)) {
+            Ok(ast) => ast,
+            // A file that doesn't parse has no reliable symbols to report.
+            Err(_) => return Ok(None),
+        };
+        Ok(Some(DocumentSymbolResponse::Flat(symbols(
+            &uri, &text, &ast,
+        ))))
+    }
+}
+
+/// Map a parser diagnostic onto an LSP diagnostic.
+fn to_lsp_diagnostic(diagnostic: &crate::compiler::parser::Diagnostic) -> Diagnostic {
+    let start = to_position(diagnostic.span.start);
+    let mut end = to_position(diagnostic.span.end);
+    // Widen a zero-length span by one character so it is visible in the editor.
+    if end == start {
+        end.character += 1;
+    }
+    Diagnostic {
+        range: Range::new(start, end),
+        severity: Some(DiagnosticSeverity::Error),
+        source: Some("roolz".to_string()),
+        message: diagnostic.message.clone(),
+        ..Diagnostic::default()
+    }
+}
+
+/// Convert a parser `(line, column)` pair (both 1-based) to an LSP position.
+fn to_position((line, column): (usize, usize)) -> Position {
+    Position::new(
+        line.saturating_sub(1) as u32,
+        column.saturating_sub(1) as u32,
+    )
+}
+
+/// Flatten an AST into the symbols exposed via `documentSymbol`.
+fn symbols(uri: &Url, text: &str, ast: &Ast) -> Vec<SymbolInformation> {
+    let mut symbols = Vec::new();
+    let package = ast.package.name.join(".");
+    symbols.push(symbol(uri, text, &package, SymbolKind::Package));
+    for fact in &ast.facts {
+        symbols.push(symbol(uri, text, &fact.name, SymbolKind::Field));
+    }
+    for rule in &ast.rules {
+        symbols.push(symbol(uri, text, &rule.name, SymbolKind::Function));
+    }
+    symbols
+}
+
+#[allow(deprecated)]
+fn symbol(uri: &Url, text: &str, name: &str, kind: SymbolKind) -> SymbolInformation {
+    SymbolInformation {
+        name: name.to_string(),
+        kind,
+        tags: None,
+        deprecated: None,
+        location: Location::new(uri.clone(), declaration_range(text, name)),
+        container_name: None,
+    }
+}
+
+/// Best-effort range for a declaration, anchored at the line where its name appears.
+fn declaration_range(text: &str, name: &str) -> Range {
+    for (line, contents) in text.lines().enumerate() {
+        if let Some(column) = contents.find(name) {
+            let start = Position::new(line as u32, column as u32);
+            let end = Position::new(line as u32, (column + name.len()) as u32);
+            return Range::new(start, end);
+        }
+    }
+    Range::default()
+}
@@ -0,0 +1,162 @@
+//! A resilient client session that survives transport drops.
+//!
+//! [`Session`] wraps [`RulesServiceClient`] with automatic reconnection: when
+//! the bidirectional stream breaks it re-dials with capped exponential backoff
+//! (plus jitter), re-opens the `session` RPC, and replays its resume token so
+//! the server continues the existing evaluation instead of restarting. Callers
+//! drive it over a pair of channels and never see the reconnects, mirroring how
+//! a long-lived network source keeps its connection alive transparently.
+
+use std::{error::Error, time::Duration};
+
+use tokio::{sync::mpsc, time};
+use tonic::{transport::Endpoint, Request};
+use uuid::Uuid;
+
+use crate::api::v1alpha::service::{RulesServiceClient, SessionRequest, SessionResponse};
+
+/// A reconnecting handle to the rules service.
+pub struct Session {
+    endpoint: Endpoint,
+    id: Uuid,
+}
+
+/// Internal marker signalling that the stream dropped and should be re-dialed.
+struct Dropped;
+
+impl Session {
+    /// Create a session for `endpoint`, assigning it a fresh resume token.
+    pub fn new(endpoint: Endpoint) -> Session {
+        Session {
+            endpoint,
+            id: Uuid::new_v4(),
+        }
+    }
+
+    /// The session's resume token.
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// Drive the session until `outbound` is closed by the caller or the
+    /// receiver of `inbound` is dropped.
+    ///
+    /// Requests sent on `outbound` are forwarded to the server and responses are
+    /// delivered on `inbound`. Transport failures are handled internally by
+    /// reconnecting; a request in flight when the stream drops may be lost, but
+    /// the resume token lets the server pick up where it left off.
+    pub async fn run(
+        &mut self,
+        mut outbound: mpsc::UnboundedReceiver<SessionRequest>,
+        inbound: mpsc::UnboundedSender<SessionResponse>,
+    ) {
+        loop {
+            let mut client = self.connect_with_retry().await;
+            match self.pump(&mut client, &mut outbound, &inbound).await {
+                // Caller hung up: the session is finished.
+                Ok(()) => return,
+                // Stream dropped: reconnect and resume.
+                Err(Dropped) => log::warn!("Session {} dropped; reconnecting...", self.id),
+            }
+        }
+    }
+
+    /// Re-dial the endpoint until a connection succeeds, backing off between
+    /// attempts.
+    async fn connect_with_retry(&self) -> RulesServiceClient<tonic::transport::Channel> {
+        let mut backoff = Backoff::new();
+        loop {
+            match RulesServiceClient::connect(self.endpoint.clone()).await {
+                Ok(client) => return client,
+                Err(err) => {
+                    let wait = backoff.next();
+                    log::warn!("Connect failed ({}); retrying in {:?}", err, wait);
+                    time::delay_for(wait).await;
+                }
+            }
+        }
+    }
+
+    /// Open the bidirectional stream and pump requests/responses until it ends.
+    async fn pump(
+        &self,
+        client: &mut RulesServiceClient<tonic::transport::Channel>,
+        outbound: &mut mpsc::UnboundedReceiver<SessionRequest>,
+        inbound: &mpsc::UnboundedSender<SessionResponse>,
+    ) -> Result<(), Dropped> {
+        let (conn_tx, mut conn_rx) = mpsc::unbounded_channel();
+
+        // Replay the resume token as the first message on the fresh stream.
+        let resume = SessionRequest {
+            resume_token: self.id.to_string(),
+            ..SessionRequest::default()
+        };
+        conn_tx.send(resume).ok();
+
+        let requests = async_stream::stream! {
+            while let Some(request) = conn_rx.recv().await {
+                yield request;
+            }
+        };
+
+        let response = client
+            .session(Request::new(requests))
+            .await
+            .map_err(|_| Dropped)?;
+        let mut responses = response.into_inner();
+
+        loop {
+            tokio::select! {
+                request = outbound.recv() => match request {
+                    Some(request) => {
+                        if conn_tx.send(request).is_err() {
+                            return Err(Dropped);
+                        }
+                    }
+                    // Caller closed the outbound channel.
+                    None => return Ok(()),
+                },
+                response = responses.message() => match response {
+                    Ok(Some(response)) => {
+                        if inbound.send(response).is_err() {
+                            // Caller stopped listening.
+                            return Ok(());
+                        }
+                    }
+                    Ok(None) | Err(_) => return Err(Dropped),
+                },
+            }
+        }
+    }
+}
+
+/// Capped exponential backoff starting at 100ms, with jitter derived from a
+/// fresh UUID so we avoid pulling in a separate RNG dependency.
+struct Backoff {
+    current: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    fn new() -> Backoff {
+        Backoff {
+            current: Duration::from_millis(100),
+            max: Duration::from_secs(30),
+        }
+    }
+
+    /// The next interval to wait, advancing the backoff toward its cap.
+    fn next(&mut self) -> Duration {
+        let base = self.current.as_millis() as u64;
+        let jitter = (Uuid::new_v4().as_u128() as u64) % (base / 2 + 1);
+        let wait = Duration::from_millis(base + jitter);
+        self.current = (self.current * 2).min(self.max);
+        wait
+    }
+}
+
+/// Build an [`Endpoint`] from a URI string, for callers that just want a
+/// resilient session without touching `tonic` directly.
+pub fn endpoint(uri: impl Into<String>) -> Result<Endpoint, Box<dyn Error>> {
+    Ok(Endpoint::from_shared(uri.into())?)
+}